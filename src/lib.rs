@@ -1,29 +1,24 @@
 use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::ops::Range;
+#[cfg(not(feature = "memchr"))]
 use std::str::CharIndices;
 
-const LINE_SKIP_THRESOLD: usize = 10;
-const HIGHER_LINE_SKIP_THRESOLD: usize = 100;
-const EXTRA_HIGHER_LINE_SKIP_THRESOLD: usize = 1_000;
-
 /// Contains source text and line locations.
 pub struct SourceText {
     pub contents: String,
     processed_lines: Cell<bool>,
 
-    /// Collection of ascending line number *skips* used
-    /// for optimizing retrieval of line numbers or line offsets.
-    pub(crate) line_skips: RefCell<Vec<LineSkip>>,
-    pub(crate) line_skips_counter: Cell<usize>,
-
-    /// Collection used before `line_skips` in line lookups
-    /// to skip lines in a higher threshold.
-    pub(crate) higher_line_skips: RefCell<Vec<HigherLineSkip>>,
-    pub(crate) higher_line_skips_counter: Cell<usize>,
+    /// Ascending byte offsets of the start of every line, with
+    /// `line_starts[0] == 0`. Line `n` (counting from one) starts
+    /// at `line_starts[n - 1]`. Lookups binary search this table,
+    /// mirroring rustc's `CodeMap`.
+    pub(crate) line_starts: RefCell<Vec<usize>>,
 
-    /// Collection used before `higher_line_skips` in line lookups
-    /// to skip lines in an extra higher threshold.
-    pub(crate) extra_higher_line_skips: RefCell<Vec<HigherLineSkip>>,
-    pub(crate) extra_higher_line_skips_counter: Cell<usize>
+    /// Bumped on every `replace_range()` edit. Exposed via `version()`
+    /// so external callers that cache offsets can tell whether
+    /// `contents` has changed since they were computed.
+    version: Cell<usize>,
 }
 
 impl SourceText {
@@ -31,12 +26,100 @@ impl SourceText {
         Self {
             contents,
             processed_lines: Cell::new(false),
-            line_skips: RefCell::new(vec![LineSkip { offset: 0, line_number: 1 }]),
-            line_skips_counter: Cell::new(0),
-            higher_line_skips: RefCell::new(vec![HigherLineSkip { skip_index: 0, offset: 0, line_number: 1 }]),
-            higher_line_skips_counter: Cell::new(0),
-            extra_higher_line_skips: RefCell::new(vec![HigherLineSkip { skip_index: 0, offset: 0, line_number: 1 }]),
-            extra_higher_line_skips_counter: Cell::new(0),
+            line_starts: RefCell::new(vec![0]),
+            version: Cell::new(0),
+        }
+    }
+
+    /// Returns the current revision number, bumped by every
+    /// `replace_range()` call.
+    pub fn version(&self) -> usize {
+        self.version.get()
+    }
+
+    /// Replaces the byte `range` of `contents` with `replacement`,
+    /// repairing the line-start index in place instead of reprocessing
+    /// the whole file: starts up to `range.start` are kept, starts after
+    /// `range.start` up to and including `range.end` are dropped, starts
+    /// after the edit are
+    /// shifted by the length delta, and the new interior line starts
+    /// introduced by `replacement` are spliced in. `\r`/`\n` adjacency
+    /// straddling the edit boundaries is checked separately, since a
+    /// `\r\n` pair can be split or formed without either half lying
+    /// inside `replacement` itself.
+    pub fn replace_range(&mut self, range: Range<usize>, replacement: &str) {
+        self.process_lines();
+
+        let bytes = self.contents.as_bytes();
+        let prev_byte = range.start.checked_sub(1).and_then(|i| bytes.get(i).copied());
+        let old_byte_at_start = bytes.get(range.start).copied();
+        let next_byte = bytes.get(range.end).copied();
+
+        // What now sits right after `prev_byte` / right before `next_byte`,
+        // accounting for `replacement` possibly being empty.
+        let new_byte_at_start = if replacement.is_empty() { next_byte } else { replacement.as_bytes().first().copied() };
+        let was_crlf_at_start = prev_byte == Some(b'\r') && old_byte_at_start == Some(b'\n');
+        let is_crlf_at_start = prev_byte == Some(b'\r') && new_byte_at_start == Some(b'\n');
+
+        let shift = replacement.len() as isize - (range.end - range.start) as isize;
+
+        self.contents.replace_range(range.clone(), replacement);
+        self.version.set(self.version.get() + 1);
+
+        let mut line_starts = self.line_starts.borrow_mut();
+        // An entry exactly at `range.end` was produced by a terminator
+        // whose last byte(s) sit right before it; for a non-empty range
+        // that byte was inside `[range.start, range.end)` and is gone
+        // now, so the entry is stale and must be dropped rather than
+        // kept (it would otherwise collide with whatever the interior
+        // scan or a pure insertion re-derives at that same offset). For
+        // an empty range (`range.start == range.end`) nothing was
+        // actually consumed, so that same offset is covered by the
+        // `o <= range.start` arm instead and stays put.
+        line_starts.retain(|&o| o <= range.start || o > range.end);
+
+        // A `\r` just before the edit used to stand alone and has a
+        // line-start entry at `range.start`; it's now paired with a `\n`
+        // that immediately follows, so that stale entry must go — the
+        // pair's real break is the `\n`'s own (still-correct) entry.
+        if is_crlf_at_start && !was_crlf_at_start {
+            if let Ok(idx) = line_starts.binary_search(&range.start) {
+                line_starts.remove(idx);
+            }
+        }
+
+        for o in line_starts.iter_mut() {
+            if *o > range.end {
+                *o = (*o as isize + shift) as usize;
+            }
+        }
+
+        // Conversely, a `\r\n` pair straddling the edit's start that's
+        // now broken apart means the `\r` is lone and needs a line-start
+        // entry of its own, which never existed before.
+        if was_crlf_at_start && !is_crlf_at_start {
+            if let Err(idx) = line_starts.binary_search(&range.start) {
+                line_starts.insert(idx, range.start);
+            }
+        }
+
+        let insert_at = line_starts.partition_point(|&o| o <= range.start);
+        let mut interior = Vec::new();
+        #[cfg(feature = "memchr")]
+        Self::scan_line_starts_memchr(replacement, &mut interior);
+        #[cfg(not(feature = "memchr"))]
+        Self::scan_line_starts_chars(replacement, &mut interior);
+
+        // A trailing lone `\r` in `replacement` is scanned as if nothing
+        // follows it. If it's actually paired with the preserved `\n`
+        // right after the edit, that entry is spurious — the pair's real
+        // break is the `\n`'s own (already-shifted) line start.
+        if replacement.as_bytes().last() == Some(&b'\r') && next_byte == Some(b'\n') && interior.last() == Some(&replacement.len()) {
+            interior.pop();
+        }
+
+        for (i, o) in interior.into_iter().enumerate() {
+            line_starts.insert(insert_at + i, range.start + o);
         }
     }
 
@@ -45,210 +128,104 @@ impl SourceText {
             return;
         }
         self.processed_lines.set(true);
-        let mut s = CharacterReader::from(&self.contents);
-        let mut line: usize = 1;
+        let mut line_starts = self.line_starts.borrow_mut();
+        #[cfg(feature = "memchr")]
+        Self::scan_line_starts_memchr(&self.contents, &mut line_starts);
+        #[cfg(not(feature = "memchr"))]
+        Self::scan_line_starts_chars(&self.contents, &mut line_starts);
+    }
+
+    /// Char-by-char line scan, used when the `memchr` feature is disabled.
+    #[cfg(not(feature = "memchr"))]
+    fn scan_line_starts_chars(contents: &str, line_starts: &mut Vec<usize>) {
+        let mut s = CharacterReader::from(contents);
         while s.has_remaining() {
             let ch = s.next_or_zero();
             if CharacterValidator::is_line_terminator(ch) {
                 if ch == '\r' && s.peek_or_zero() == '\n' {
                     s.next();
                 }
-                line += 1;
-                self.push_line_skip(line, s.index());
+                line_starts.push(s.index());
             }
         }
     }
 
-    fn push_line_skip(&self, line_number: usize, offset: usize) {
-        let counter = self.line_skips_counter.get();
-        if counter == LINE_SKIP_THRESOLD {
-            self.line_skips.borrow_mut().push(LineSkip { line_number, offset });
-            self.line_skips_counter.set(0);
-        } else {
-            self.line_skips_counter.set(counter + 1);
-        }
-
-        let counter = self.higher_line_skips_counter.get();
-        if counter == HIGHER_LINE_SKIP_THRESOLD {
-            self.higher_line_skips.borrow_mut().push(HigherLineSkip { skip_index: self.line_skips.borrow().len() - 1, line_number, offset });
-            self.higher_line_skips_counter.set(0);
-        } else {
-            self.higher_line_skips_counter.set(counter + 1);
+    /// Byte-level line scan over raw UTF-8 bytes, accelerated with
+    /// `memchr`. Finds `\n`/`\r` directly and the lead/trail byte pairs
+    /// of U+2028/U+2029 (`0xE2 0x80 0xA8`/`0xE2 0x80 0xA9`), which are
+    /// never mistaken for continuation bytes of other code points.
+    #[cfg(feature = "memchr")]
+    fn scan_line_starts_memchr(contents: &str, line_starts: &mut Vec<usize>) {
+        let bytes = contents.as_bytes();
+        let mut ascii = memchr::memchr2_iter(b'\n', b'\r', bytes).peekable();
+        let mut unicode = memchr::memchr_iter(0xE2, bytes).peekable();
+        let mut consumed = 0usize;
+        loop {
+            while ascii.peek().is_some_and(|&p| p < consumed) {
+                ascii.next();
+            }
+            while unicode.peek().is_some_and(|&p| {
+                p < consumed
+                    || bytes.get(p + 1) != Some(&0x80)
+                    || !matches!(bytes.get(p + 2), Some(&0xA8) | Some(&0xA9))
+            }) {
+                unicode.next();
+            }
+            match (ascii.peek().copied(), unicode.peek().copied()) {
+                (None, None) => break,
+                (Some(a), None) => {
+                    ascii.next();
+                    consumed = Self::push_ascii_terminator(bytes, a, line_starts);
+                }
+                (None, Some(u)) => {
+                    unicode.next();
+                    consumed = u + 3;
+                    line_starts.push(consumed);
+                }
+                (Some(a), Some(u)) => {
+                    if a <= u {
+                        ascii.next();
+                        consumed = Self::push_ascii_terminator(bytes, a, line_starts);
+                    } else {
+                        unicode.next();
+                        consumed = u + 3;
+                        line_starts.push(consumed);
+                    }
+                }
+            }
         }
+    }
 
-        let counter = self.extra_higher_line_skips_counter.get();
-        if counter == EXTRA_HIGHER_LINE_SKIP_THRESOLD {
-            self.extra_higher_line_skips.borrow_mut().push(HigherLineSkip { skip_index: self.higher_line_skips.borrow().len() - 1, line_number, offset });
-            self.extra_higher_line_skips_counter.set(0);
+    /// Pushes the line start following a `\n` or `\r` at `pos`, coalescing
+    /// a `\r\n` pair into a single line break. Returns the new line start.
+    #[cfg(feature = "memchr")]
+    fn push_ascii_terminator(bytes: &[u8], pos: usize, line_starts: &mut Vec<usize>) -> usize {
+        let next_start = if bytes[pos] == b'\r' && bytes.get(pos + 1) == Some(&b'\n') {
+            pos + 2
         } else {
-            self.extra_higher_line_skips_counter.set(counter + 1);
-        }
+            pos + 1
+        };
+        line_starts.push(next_start);
+        next_start
     }
 
     /// Retrieves line number from an offset. The resulting line number
     /// is counted from one.
     pub fn get_line_number(&self, offset: usize) -> usize {
         self.process_lines();
-
-        // Extra higher line skips
-        let mut last_skip = HigherLineSkip { skip_index: 0, offset: 0, line_number: 1 };
-        let skips = self.extra_higher_line_skips.borrow();
-        let mut skips = skips.iter();
-        while let Some(skip_1) = skips.next() {
-            if offset < skip_1.offset {
-                break;
-            }
-            last_skip = *skip_1;
-        }
-
-        // Higher line skips
-        let skips = self.higher_line_skips.borrow();
-        let mut skips = skips[last_skip.skip_index..].iter();
-        let mut last_skip = skips.next().unwrap();
-        while let Some(skip_1) = skips.next() {
-            if offset < skip_1.offset {
-                break;
-            }
-            last_skip = skip_1;
-        }
-
-        // Line skips
-        let skips = self.line_skips.borrow();
-        let mut skips = skips[last_skip.skip_index..].iter();
-        let mut last_skip = skips.next().unwrap();
-        while let Some(skip_1) = skips.next() {
-            if offset < skip_1.offset {
-                break;
-            }
-            last_skip = skip_1;
-        }
-
-        let mut current_line = last_skip.line_number;
-        let mut characters = CharacterReader::from(&self.contents[last_skip.offset..]);
-        while last_skip.offset + characters.index() < offset {
-            let ch_1 = characters.next();
-            if let Some(ch_1) = ch_1 {
-                if CharacterValidator::is_line_terminator(ch_1) {
-                    if ch_1 == '\r' && characters.peek_or_zero() == '\n' {
-                        characters.next();
-                    }
-                    current_line += 1;
-                }
-            } else {
-                break;
-            }
-        }
-        current_line
+        self.line_starts.borrow().partition_point(|&o| o <= offset)
     }
 
     /// Retrieves offset from line number (counted from one).
     pub fn get_line_offset(&self, line: usize) -> Option<usize> {
         self.process_lines();
-
-        // Extra higher line skips
-        let mut last_skip = HigherLineSkip { skip_index: 0, offset: 0, line_number: 1 };
-        let skips = self.extra_higher_line_skips.borrow();
-        let mut skips = skips.iter();
-        while let Some(skip_1) = skips.next() {
-            if line < skip_1.line_number {
-                break;
-            }
-            last_skip = *skip_1;
-        }
-
-        // Higher line skips
-        let skips = self.higher_line_skips.borrow();
-        let mut skips = skips[last_skip.skip_index..].iter();
-        let mut last_skip = skips.next().unwrap();
-        while let Some(skip_1) = skips.next() {
-            if line < skip_1.line_number {
-                break;
-            }
-            last_skip = skip_1;
-        }
-
-        // Line skips
-        let skips = self.line_skips.borrow();
-        let mut skips = skips[last_skip.skip_index..].iter();
-        let mut last_skip = skips.next().unwrap();
-        while let Some(skip_1) = skips.next() {
-            if line < skip_1.line_number {
-                break;
-            }
-            last_skip = skip_1;
-        }
-
-        let mut current_line = last_skip.line_number;
-        let mut characters = CharacterReader::from(&self.contents[last_skip.offset..]);
-        while current_line != line {
-            let ch_1 = characters.next();
-            if let Some(ch_1) = ch_1 {
-                if CharacterValidator::is_line_terminator(ch_1) {
-                    if ch_1 == '\r' && characters.peek_or_zero() == '\n' {
-                        characters.next();
-                    }
-                    current_line += 1;
-                }
-            } else {
-                return None;
-            }
-        }
-        Some(last_skip.offset + characters.index())
+        self.line_starts.borrow().get(line - 1).copied()
     }
 
     /// Retrieves the offset from the corresponding line of an offset.
     pub fn get_line_offset_from_offset(&self, offset: usize) -> usize {
         self.process_lines();
-
-        // Extra higher line skips
-        let mut last_skip = HigherLineSkip { skip_index: 0, offset: 0, line_number: 1 };
-        let skips = self.extra_higher_line_skips.borrow();
-        let mut skips = skips.iter();
-        while let Some(skip_1) = skips.next() {
-            if offset < skip_1.offset {
-                break;
-            }
-            last_skip = *skip_1;
-        }
-
-        // Higher line skips
-        let skips = self.higher_line_skips.borrow();
-        let mut skips = skips[last_skip.skip_index..].iter();
-        let mut last_skip = skips.next().unwrap();
-        while let Some(skip_1) = skips.next() {
-            if offset < skip_1.offset {
-                break;
-            }
-            last_skip = skip_1;
-        }
-
-        // Line skips
-        let skips = self.line_skips.borrow();
-        let mut skips = skips[last_skip.skip_index..].iter();
-        let mut last_skip = skips.next().unwrap();
-        while let Some(skip_1) = skips.next() {
-            if offset < skip_1.offset {
-                break;
-            }
-            last_skip = skip_1;
-        }
-
-        let mut current_line_offset = last_skip.offset;
-        let mut characters = CharacterReader::from(&self.contents[last_skip.offset..]);
-        while last_skip.offset + characters.index() < offset {
-            let ch_1 = characters.next();
-            if let Some(ch_1) = ch_1 {
-                if CharacterValidator::is_line_terminator(ch_1) {
-                    if ch_1 == '\r' && characters.peek_or_zero() == '\n' {
-                        characters.next();
-                    }
-                    current_line_offset = last_skip.offset + characters.index();
-                }
-            } else {
-                break;
-            }
-        }
-        current_line_offset
+        self.line_starts.borrow()[self.get_line_number(offset) - 1]
     }
 
     /// Returns the zero based column of an offset.
@@ -266,33 +243,159 @@ impl SourceText {
         }
         i
     }
+
+    /// Enumerates every line touched by `range`, for caret-annotated
+    /// diagnostic output. A single-line span yields one record; a
+    /// multi-line span yields one per line from the start line through
+    /// the end line, inclusive.
+    pub fn span_to_lines(&self, range: Range<usize>) -> Vec<LineRecord<'_>> {
+        self.process_lines();
+
+        let start_line = self.get_line_number(range.start);
+        let mut end_line = self.get_line_number(range.end);
+
+        // `range.end` landing exactly on a line start means the span
+        // doesn't actually touch that line — it ends right at the
+        // boundary — so the last real line is the one before it, as
+        // with rustc's `span_to_lines`.
+        if start_line < end_line && self.get_line_offset(end_line) == Some(range.end) {
+            end_line -= 1;
+        }
+
+        let mut lines = Vec::with_capacity(end_line - start_line + 1);
+        for line_number in start_line..=end_line {
+            let start = self.get_line_offset(line_number).unwrap();
+            let raw_end = self.get_line_offset(line_number + 1).unwrap_or(self.contents.len());
+            let end = start + Self::trim_line_terminator(&self.contents[start..raw_end]);
+            let text = &self.contents[start..end];
+
+            let start_column = if line_number == start_line { self.get_column(range.start) } else { 0 };
+            let end_column = if line_number == end_line && range.end < raw_end { self.get_column(range.end) } else { text.chars().count() };
+
+            lines.push(LineRecord { line_number, start, end, text, start_column, end_column });
+        }
+        lines
+    }
+
+    /// Returns the byte length of `line` up to (excluding) its trailing
+    /// line terminator, if any.
+    fn trim_line_terminator(line: &str) -> usize {
+        let mut chars = line.char_indices().rev();
+        if let Some((i, ch)) = chars.next() {
+            if ch == '\x0A' || ch == '\x0D' || ch == '\u{2028}' || ch == '\u{2029}' {
+                if ch == '\n' {
+                    if let Some((j, '\r')) = chars.next() {
+                        return j;
+                    }
+                }
+                return i;
+            }
+        }
+        line.len()
+    }
 }
 
-#[derive(Copy, Clone)]
-struct LineSkip {
-    /// Line offset.
-    pub offset: usize,
-    /// Line number counting from one.
+/// A single line's contribution to a `span_to_lines()` result.
+pub struct LineRecord<'a> {
+    /// The line number, counted from one.
     pub line_number: usize,
+    /// The byte offset where the line starts.
+    pub start: usize,
+    /// The byte offset where the line ends, excluding its terminator.
+    pub end: usize,
+    /// The line's text, excluding its terminator.
+    pub text: &'a str,
+    /// The zero-based column where the span starts on this line.
+    pub start_column: usize,
+    /// The zero-based column where the span ends on this line.
+    pub end_column: usize,
 }
 
-#[derive(Copy, Clone)]
-struct HigherLineSkip {
-    /// Index to a `LineSkip`, or another `HigherLineSkip` in the case
-    /// of extra higher line skips.
-    pub skip_index: usize,
-    /// Line offset.
-    pub offset: usize,
-    /// Line number counting from one.
-    pub line_number: usize,
+/// A cursor into a `SourceText`, holding a byte offset. Computes line
+/// and column lazily from the nearest line start rather than eagerly
+/// tracking both.
+#[derive(Clone, Copy)]
+pub struct Position<'a> {
+    source: &'a SourceText,
+    offset: usize,
+}
+
+impl<'a> Position<'a> {
+    pub fn new(source: &'a SourceText, offset: usize) -> Self {
+        Self { source, offset }
+    }
+
+    /// Returns the byte offset of this position.
+    pub fn pos(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the 1-based line number and the zero-based column,
+    /// computed in a single traversal from the nearest line start.
+    pub fn line_col(&self) -> (usize, usize) {
+        let line = self.source.get_line_number(self.offset);
+        let line_start = self.source.line_starts.borrow()[line - 1];
+        let column = self.source.contents[line_start..self.offset].chars().count();
+        (line, column)
+    }
+}
+
+impl<'a> PartialEq for Position<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.offset == other.offset
+    }
+}
+
+impl<'a> Eq for Position<'a> {}
+
+impl<'a> PartialOrd for Position<'a> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
+impl<'a> Ord for Position<'a> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.offset.cmp(&other.offset)
+    }
+}
+
+/// A byte range into a `SourceText`, expressed as a pair of `Position`s.
+#[derive(Clone, Copy)]
+pub struct Span<'a> {
+    start: Position<'a>,
+    end: Position<'a>,
+}
+
+impl<'a> Span<'a> {
+    pub fn new(start: Position<'a>, end: Position<'a>) -> Self {
+        Self { start, end }
+    }
+
+    /// Returns the position at the start of the span.
+    pub fn start_pos(&self) -> Position<'a> {
+        self.start
+    }
+
+    /// Returns the position at the end of the span.
+    pub fn end_pos(&self) -> Position<'a> {
+        self.end
+    }
+
+    /// Returns the slice of source text covered by the span.
+    pub fn as_str(&self) -> &'a str {
+        &self.start.source.contents[self.start.offset..self.end.offset]
+    }
+}
+
+#[cfg(not(feature = "memchr"))]
 #[derive(Clone)]
 struct CharacterReader<'a> {
     length: usize,
     char_indices: CharIndices<'a>,
 }
 
+#[cfg(not(feature = "memchr"))]
 impl<'a> CharacterReader<'a> {
     /// Indicates if there are remaining code points to read.
     pub fn has_remaining(&self) -> bool {
@@ -322,6 +425,7 @@ impl<'a> CharacterReader<'a> {
     }
 }
 
+#[cfg(not(feature = "memchr"))]
 impl<'a> From<&'a str> for CharacterReader<'a> {
     /// Constructs a `CharacterReader` from a string.
     fn from(value: &'a str) -> Self {
@@ -329,6 +433,7 @@ impl<'a> From<&'a str> for CharacterReader<'a> {
     }
 }
 
+#[cfg(not(feature = "memchr"))]
 impl<'a> From<&'a String> for CharacterReader<'a> {
     /// Constructs a `CharacterReader` from a string.
     fn from(value: &'a String) -> Self {
@@ -336,6 +441,7 @@ impl<'a> From<&'a String> for CharacterReader<'a> {
     }
 }
 
+#[cfg(not(feature = "memchr"))]
 impl<'a> Iterator for CharacterReader<'a> {
     type Item = char;
 
@@ -344,8 +450,10 @@ impl<'a> Iterator for CharacterReader<'a> {
     }
 }
 
+#[cfg(not(feature = "memchr"))]
 struct CharacterValidator;
 
+#[cfg(not(feature = "memchr"))]
 impl CharacterValidator {
     pub fn is_line_terminator(ch: char) -> bool {
         ch == '\x0A' || ch == '\x0D' || ch == '\u{2028}' || ch == '\u{2029}'
@@ -376,4 +484,139 @@ mod tests {
         assert_eq!(2, text.get_line_number(24));
         assert_eq!(23, text.get_column(24));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_replace_range() {
+        let mut text = SourceText::new("foo\nbar\nqux".into());
+        assert_eq!(0, text.version());
+
+        // Replace "bar" (no line breaks) with a three-line replacement.
+        text.replace_range(4..7, "a\nb\nc");
+        assert_eq!("foo\na\nb\nc\nqux", text.contents);
+        assert_eq!(1, text.version());
+        assert_eq!(1, text.get_line_number(0));
+        assert_eq!(2, text.get_line_number(4));
+        assert_eq!(3, text.get_line_number(6));
+        assert_eq!(4, text.get_line_number(8));
+        assert_eq!(5, text.get_line_number(10));
+        assert_eq!(Some(10), text.get_line_offset(5));
+
+        // Collapse the inserted lines back down.
+        text.replace_range(4..9, "bar");
+        assert_eq!("foo\nbar\nqux", text.contents);
+        assert_eq!(2, text.version());
+        assert_eq!(1, text.get_line_number(0));
+        assert_eq!(2, text.get_line_number(4));
+        assert_eq!(3, text.get_line_number(8));
+        assert_eq!(None, text.get_line_offset(4));
+    }
+
+    #[test]
+    fn test_replace_range_crlf_adjacency() {
+        // Splitting an existing "\r\n" pair by inserting between the two
+        // bytes must keep the lone "\r" as its own line break.
+        let mut text = SourceText::new("foo\r\nbar".into());
+        text.replace_range(4..4, "Z");
+        assert_eq!("foo\rZ\nbar", text.contents);
+        let rebuilt = SourceText::new(text.contents.clone());
+        rebuilt.process_lines();
+        assert_eq!(rebuilt.line_starts.borrow().clone(), text.line_starts.borrow().clone());
+
+        // Turning a lone "\r" into a "\r\n" pair by inserting a "\n" right
+        // after it must drop the stale entry for the lone "\r".
+        let mut text = SourceText::new("foo\rbar".into());
+        text.replace_range(4..4, "\n");
+        assert_eq!("foo\r\nbar", text.contents);
+        let rebuilt = SourceText::new(text.contents.clone());
+        rebuilt.process_lines();
+        assert_eq!(rebuilt.line_starts.borrow().clone(), text.line_starts.borrow().clone());
+
+        // A replacement ending in a lone "\r" keeps its own break when no
+        // "\n" follows it, and must not double-count the break when it
+        // turns out to be immediately followed by a preserved "\n".
+        let mut text = SourceText::new("foo\nbar".into());
+        text.replace_range(3..4, "\r");
+        assert_eq!("foo\rbar", text.contents);
+        let rebuilt = SourceText::new(text.contents.clone());
+        rebuilt.process_lines();
+        assert_eq!(rebuilt.line_starts.borrow().clone(), text.line_starts.borrow().clone());
+
+        let mut text = SourceText::new("foo\nbar".into());
+        text.replace_range(3..3, "\r");
+        assert_eq!("foo\r\nbar", text.contents);
+        let rebuilt = SourceText::new(text.contents.clone());
+        rebuilt.process_lines();
+        assert_eq!(rebuilt.line_starts.borrow().clone(), text.line_starts.borrow().clone());
+    }
+
+    #[test]
+    fn test_position_span() {
+        use super::{Position, Span};
+
+        let text = SourceText::new("foo\r\nbar\r\nqux".into());
+        let start = Position::new(&text, 5);
+        let end = Position::new(&text, 8);
+        assert_eq!((2, 0), start.line_col());
+        assert_eq!((2, 3), end.line_col());
+        assert!(start < end);
+
+        let span = Span::new(start, end);
+        assert_eq!(5, span.start_pos().pos());
+        assert_eq!(8, span.end_pos().pos());
+        assert_eq!("bar", span.as_str());
+    }
+
+    #[test]
+    fn test_span_to_lines() {
+        let text = SourceText::new("foo\r\nbar\r\nqux".into());
+
+        // Single-line span.
+        let lines = text.span_to_lines(5..8);
+        assert_eq!(1, lines.len());
+        assert_eq!(2, lines[0].line_number);
+        assert_eq!(5, lines[0].start);
+        assert_eq!(8, lines[0].end);
+        assert_eq!("bar", lines[0].text);
+        assert_eq!(0, lines[0].start_column);
+        assert_eq!(3, lines[0].end_column);
+
+        // Multi-line span.
+        let lines = text.span_to_lines(2..11);
+        assert_eq!(3, lines.len());
+
+        assert_eq!(1, lines[0].line_number);
+        assert_eq!(0, lines[0].start);
+        assert_eq!(3, lines[0].end);
+        assert_eq!("foo", lines[0].text);
+        assert_eq!(2, lines[0].start_column);
+        assert_eq!(3, lines[0].end_column);
+
+        assert_eq!(2, lines[1].line_number);
+        assert_eq!("bar", lines[1].text);
+        assert_eq!(0, lines[1].start_column);
+        assert_eq!(3, lines[1].end_column);
+
+        assert_eq!(3, lines[2].line_number);
+        assert_eq!(10, lines[2].start);
+        assert_eq!(13, lines[2].end);
+        assert_eq!("qux", lines[2].text);
+        assert_eq!(0, lines[2].start_column);
+        assert_eq!(1, lines[2].end_column);
+    }
+
+    #[test]
+    fn test_span_to_lines_end_on_line_start() {
+        // `range.end` landing exactly on the next line's start means the
+        // span doesn't reach into that line, so only one record comes
+        // back, not two.
+        let text = SourceText::new("foo\nbar\nqux".into());
+        let lines = text.span_to_lines(0..4);
+        assert_eq!(1, lines.len());
+        assert_eq!(1, lines[0].line_number);
+        assert_eq!(0, lines[0].start);
+        assert_eq!(3, lines[0].end);
+        assert_eq!("foo", lines[0].text);
+        assert_eq!(0, lines[0].start_column);
+        assert_eq!(3, lines[0].end_column);
+    }
+}